@@ -0,0 +1,3 @@
+mod pages_storage;
+
+pub use pages_storage::{DefaultPagesStorage, PageDiff, TwoPhaseCommit};
@@ -1,13 +1,26 @@
 use crate::page::PageIndex;
 use crate::traits::{KVStore, PageIndexHasher, PagesStorage};
 
-use svm_common::Address;
+use svm_common::{Address, DefaultKeyHasher, KeyHasher};
 
 use std::cell::RefCell;
 use std::collections::HashMap;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
+/// Controls how `DefaultPagesStorage` lays out a page's data in the underlying key-value store.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum AddressingMode {
+    /// Each page is stored directly under its `page-key` (`page-key -> data`). This is the historic
+    /// behavior and remains the default.
+    Direct,
+    /// Pages are addressed by the hash of their content: the blob is stored once under
+    /// `content-hash -> data` and the `page-key` holds a small indirection record
+    /// (`page-key -> content-hash`). Byte-identical pages — across slots or across addresses — share
+    /// a single immutable blob, at the cost of one extra key-value lookup per read.
+    ContentAddressed,
+}
+
 /// `DefaultPagesStorage` is the default implementation for the `PagesStorage` trait.
 /// It serves as a wrapper to a key-value store.
 ///
@@ -29,38 +42,446 @@ use std::sync::Arc;
 ///   may fail for multiple reasons, and on such occurrence we don't want to change any state.
 ///   Another benefit is that if the underlying key-value store supports a batch write (for example
 ///   databases `leveldb` and `rocksdb` have this capability), the `commit` implementation can take advantage of it.
+///
+/// * Pending changes are held as a stack of layers (`uncommitted`). A Smart Contract doing sub-calls
+///   can `checkpoint` before an inner call to open a fresh top layer, then `revert` to discard only
+///   that inner call's writes on failure, or `squash` to fold the inner layer down into the outer one
+///   on success. With no checkpoints taken the stack holds a single base layer and behaves exactly as
+///   an all-or-nothing write-set.
+///
+/// * Both the pending layers and the read-keys cache live behind a `RefCell` so that `read_page` can
+///   take `&self`. Reads therefore don't require exclusive access and can still record the pages they
+///   resolved from the underlying key-value store, sparing repeated lookups for the same `page-key`.
+///
+/// * A storage may optionally run in a content-addressed mode (see [`AddressingMode`]) via
+///   [`DefaultPagesStorage::new_content_addressed`], deduplicating byte-identical pages.
 pub struct DefaultPagesStorage<PH: PageIndexHasher, KV: KVStore> {
     addr: Address,
     kv: Arc<RefCell<KV>>,
-    uncommitted: HashMap<Vec<u8>, Vec<u8>>,
+    mode: AddressingMode,
+    uncommitted: RefCell<Vec<HashMap<Vec<u8>, Vec<u8>>>>,
+    read_cache: RefCell<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    visible_cache: RefCell<HashMap<Vec<u8>, Option<Vec<u8>>>>,
+    initial_cache: RefCell<HashMap<Vec<u8>, bool>>,
+    immutable_len: RefCell<Option<u32>>,
+    immutable_violations: Vec<PageIndex>,
     marker: PhantomData<PH>,
 }
 
+/// `PageIndex` reserved for the per-address side record holding the length of the immutable page
+/// region. No regular page may use it.
+const IMMUTABLE_META_PAGE: PageIndex = PageIndex(std::u32::MAX);
+
+/// A single pending page write, annotated with whether it allocates a brand-new `page-key` in the
+/// underlying key-value store (`is_initial == true`) or overwrites an already-committed one. Gas and
+/// storage-rent accounting can use this to charge fresh allocations differently from modifications,
+/// and block production can use it to compute a state diff.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageDiff {
+    /// The `page-key` (or, in content-addressed mode, the indirection/blob key) being written.
+    pub key: Vec<u8>,
+    /// The pending value awaiting `commit`.
+    pub data: Vec<u8>,
+    /// `true` when no value for `key` exists yet in the underlying key-value store.
+    pub is_initial: bool,
+}
+
 impl<PH, KV> DefaultPagesStorage<PH, KV>
 where
     PH: PageIndexHasher,
     KV: KVStore,
 {
-    /// Creates a new `DefaultPagesStorage`
+    /// Creates a new `DefaultPagesStorage` keying each page directly by its `page-key`.
     #[allow(unused)]
     pub fn new(addr: Address, kv: Arc<RefCell<KV>>) -> Self {
+        Self::with_mode(addr, kv, AddressingMode::Direct)
+    }
+
+    /// Creates a new `DefaultPagesStorage` that stores pages content-addressed: each distinct blob
+    /// is stored once under its content hash and every `page-key` holds an indirection record
+    /// pointing at it.
+    #[allow(unused)]
+    pub fn new_content_addressed(addr: Address, kv: Arc<RefCell<KV>>) -> Self {
+        Self::with_mode(addr, kv, AddressingMode::ContentAddressed)
+    }
+
+    fn with_mode(addr: Address, kv: Arc<RefCell<KV>>, mode: AddressingMode) -> Self {
         Self {
             addr,
             kv,
-            uncommitted: HashMap::new(),
+            mode,
+            uncommitted: RefCell::new(vec![HashMap::new()]),
+            read_cache: RefCell::new(HashMap::new()),
+            visible_cache: RefCell::new(HashMap::new()),
+            initial_cache: RefCell::new(HashMap::new()),
+            immutable_len: RefCell::new(None),
+            immutable_violations: Vec::new(),
             marker: PhantomData,
         }
     }
 
+    /// Opens a new empty top layer. Subsequent `write_page` calls land in this layer until it's
+    /// `squash`ed down into the layer beneath it or thrown away with `revert`.
+    #[allow(unused)]
+    pub fn checkpoint(&mut self) {
+        self.uncommitted.borrow_mut().push(HashMap::new());
+    }
+
+    /// Discards the top layer and all the pending changes it holds. Reverting the base layer (i.e
+    /// when no `checkpoint` is outstanding) simply empties it.
+    #[allow(unused)]
+    pub fn revert(&mut self) {
+        let mut uncommitted = self.uncommitted.borrow_mut();
+
+        if uncommitted.len() > 1 {
+            uncommitted.pop();
+        } else if let Some(top) = uncommitted.last_mut() {
+            top.clear();
+        }
+    }
+
+    /// Canonicalizes the top layer by merging it down into the layer beneath it (the top layer wins
+    /// on key conflicts). A no-op when only the base layer is present.
+    #[allow(unused)]
+    pub fn squash(&mut self) {
+        let mut uncommitted = self.uncommitted.borrow_mut();
+
+        if uncommitted.len() > 1 {
+            let top = uncommitted.pop().unwrap();
+            let below = uncommitted.last_mut().unwrap();
+
+            below.extend(top);
+        }
+    }
+
     #[must_use]
     #[inline(always)]
     fn compute_page_hash(&self, page_idx: PageIndex) -> [u8; 32] {
         PH::hash(self.addr, page_idx)
     }
 
+    #[must_use]
+    #[inline(always)]
+    fn compute_content_hash(data: &[u8]) -> [u8; 32] {
+        DefaultKeyHasher::hash(data)
+    }
+
+    /// Resolves a single key through the pending layers (top-to-bottom), then the read-keys cache,
+    /// then the committed store under the two-phase-commit visibility rule (see
+    /// `committed_visible`), caching that result whether or not a value was found.
+    fn lookup(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(value) = self.pending_lookup(key) {
+            return Some(value);
+        }
+
+        if let Some(cached) = self.read_cache.borrow().get(key) {
+            return cached.clone();
+        }
+
+        let value = self.committed_visible(key);
+        self.read_cache.borrow_mut().insert(key.to_vec(), value.clone());
+
+        value
+    }
+
+    /// Resolves a key against the pending layers only (top-to-bottom), ignoring the underlying store.
+    fn pending_lookup(&self, key: &[u8]) -> Option<Vec<u8>> {
+        for layer in self.uncommitted.borrow().iter().rev() {
+            if let Some(value) = layer.get(key) {
+                return Some(value.clone());
+            }
+        }
+
+        None
+    }
+
+    /// Resolves a committed page-key under the two-phase-commit visibility rule and recovers stale
+    /// locks left by a transaction that died mid-commit. This runs on *every* committed read — the
+    /// isolation guarantee must not depend on a caller opting in, since a stale lock from a crashed
+    /// coordinator would otherwise be read straight through as committed data. The common unlocked
+    /// page costs one extra `get` on the lock key.
+    ///
+    /// The recognized states:
+    ///
+    /// * **unlocked** — plainly visible.
+    /// * **locked, primary committed** — the lock is rolled forward and the page becomes visible.
+    /// * **locked, primary aborted** — the lock is cleaned up and the page stays invisible for good.
+    /// * **locked, primary undecided** — the transaction is still in flight (or crashed before
+    ///   settling its primary); reported not-visible *without* memoizing, so a later read re-checks
+    ///   once the coordinator — or a recovering transaction via
+    ///   [`rollback_primary`](DefaultPagesStorage::rollback_primary) — settles the primary.
+    ///
+    /// Only terminal outcomes are cached in the dedicated visibility cache, kept separate from the
+    /// raw-read cache (`read_cache`) since the two store values with different semantics. The
+    /// recovery paths physically delete the stale records (the lock on roll-forward, the lock and the
+    /// orphaned page on abort) so aborted state does not accumulate and the lock-key lookup is paid
+    /// at most once per page.
+    fn committed_visible(&self, key: &[u8]) -> Option<Vec<u8>> {
+        if let Some(cached) = self.visible_cache.borrow().get(key) {
+            return cached.clone();
+        }
+
+        // Snapshot the page and its lock under one immutable borrow, releasing it before any
+        // recovery delete (which needs `&mut`).
+        let (data, lock) = {
+            let kv = self.kv.borrow();
+            let data = kv.get(key);
+            let lock = data
+                .as_ref()
+                .and_then(|_| kv.get(Self::lock_key(key).as_ref()));
+            (data, lock)
+        };
+
+        let data = match data {
+            Some(data) => data,
+            None => {
+                self.visible_cache.borrow_mut().insert(key.to_vec(), None);
+                return None;
+            }
+        };
+
+        let primary = match lock {
+            // unlocked -> plainly visible
+            None => {
+                self.visible_cache
+                    .borrow_mut()
+                    .insert(key.to_vec(), Some(data.clone()));
+                return Some(data);
+            }
+            Some(primary) => primary,
+        };
+
+        let committed = self
+            .kv
+            .borrow()
+            .get(Self::commit_marker_key(&primary).as_ref())
+            .is_some();
+        if committed {
+            // primary committed -> roll the lock forward, deleting it so later reads see an
+            // unlocked, plainly-visible page.
+            self.kv.borrow_mut().remove(Self::lock_key(key).as_ref());
+            self.visible_cache
+                .borrow_mut()
+                .insert(key.to_vec(), Some(data.clone()));
+            return Some(data);
+        }
+
+        let aborted = self
+            .kv
+            .borrow()
+            .get(Self::abort_marker_key(&primary).as_ref())
+            .is_some();
+        if aborted {
+            // primary aborted -> the write never happened: delete the orphaned lock and page data.
+            {
+                let mut kv = self.kv.borrow_mut();
+                kv.remove(Self::lock_key(key).as_ref());
+                kv.remove(key);
+            }
+            self.visible_cache.borrow_mut().insert(key.to_vec(), None);
+            return None;
+        }
+
+        // primary undecided -> undetermined, do not memoize
+        None
+    }
+
+    #[must_use]
+    fn lock_key(page_key: &[u8]) -> [u8; 32] {
+        let mut buf = page_key.to_vec();
+        buf.extend_from_slice(b"svm:2pc:lock");
+
+        DefaultKeyHasher::hash(&buf)
+    }
+
+    #[must_use]
+    fn commit_marker_key(primary_key: &[u8]) -> [u8; 32] {
+        let mut buf = primary_key.to_vec();
+        buf.extend_from_slice(b"svm:2pc:commit");
+
+        DefaultKeyHasher::hash(&buf)
+    }
+
+    #[must_use]
+    fn abort_marker_key(primary_key: &[u8]) -> [u8; 32] {
+        let mut buf = primary_key.to_vec();
+        buf.extend_from_slice(b"svm:2pc:abort");
+
+        DefaultKeyHasher::hash(&buf)
+    }
+
+    /// Phase one of a two-phase commit (Percolator prewrite). Flushes this participant's pending
+    /// pages to the underlying store and, for each, writes a lock record pointing at the shared
+    /// `primary_key`. The pages are persisted but stay invisible to readers until the primary's
+    /// commit marker is written by [`commit_primary`](DefaultPagesStorage::commit_primary).
+    #[allow(unused)]
+    pub fn prewrite(&mut self, primary_key: &[u8]) {
+        let flat = self.flatten();
+
+        let mut records: Vec<(Vec<u8>, Vec<u8>)> = Vec::with_capacity(flat.len() * 2);
+        for (page_key, data) in flat.into_iter() {
+            // A key that already holds exactly this value needs neither a lock nor a rewrite. This
+            // matters for content-addressed blobs, which are immutable and shared across addresses:
+            // re-prewriting a byte-identical blob must not relock it, or a later abort of *this*
+            // transaction would hide a blob a previously-committed transaction still depends on.
+            if self.kv.borrow().get(&page_key).as_deref() == Some(data.as_slice()) {
+                continue;
+            }
+
+            records.push((Self::lock_key(&page_key).to_vec(), primary_key.to_vec()));
+            records.push((page_key, data));
+        }
+
+        let changes: Vec<(&[u8], &[u8])> = records
+            .iter()
+            .map(|(key, value)| (key.as_ref(), value.as_ref()))
+            .collect();
+
+        self.kv.borrow_mut().store(changes.as_slice());
+
+        self.clear();
+    }
+
+    /// Phase two of a two-phase commit. Writes the commit marker on `primary_key`, atomically
+    /// flipping every page prewritten against that primary to visible.
+    #[allow(unused)]
+    pub fn commit_primary(&mut self, primary_key: &[u8]) {
+        let key = Self::commit_marker_key(primary_key);
+        let changes = [(key.as_ref(), primary_key)];
+
+        self.kv.borrow_mut().store(&changes);
+
+        self.visible_cache.borrow_mut().clear();
+    }
+
+    /// Aborts a two-phase commit by writing the primary's abort marker. Pages prewritten against
+    /// this primary are cleaned up on the next read (they are treated as never written). A
+    /// transaction that recovers a stale lock whose primary never committed uses this to release it
+    /// deterministically.
+    #[allow(unused)]
+    pub fn rollback_primary(&mut self, primary_key: &[u8]) {
+        let key = Self::abort_marker_key(primary_key);
+        let changes = [(key.as_ref(), primary_key)];
+
+        self.kv.borrow_mut().store(&changes);
+
+        self.visible_cache.borrow_mut().clear();
+    }
+
+    /// Key of the per-address immutable-region side record. It is domain-separated out of the
+    /// page-key namespace (a second hash over the reserved page-key with a dedicated tag) so no
+    /// `write_page`, at any page index, can land on it and forge the immutable metadata.
+    #[must_use]
+    #[inline(always)]
+    fn immutable_meta_key(&self) -> [u8; 32] {
+        let base = PH::hash(self.addr, IMMUTABLE_META_PAGE);
+
+        let mut buf = base.to_vec();
+        buf.extend_from_slice(b"svm:immutable:meta");
+
+        DefaultKeyHasher::hash(&buf)
+    }
+
+    /// Marks pages `[0, len)` as immutable for this address and records the region's length under a
+    /// dedicated per-address side key. Intended to be called once, at deploy time; the length awaits
+    /// `commit` together with the rest of the deploy's pages.
+    #[allow(unused)]
+    pub fn set_immutable_len(&mut self, len: u32) {
+        let key = self.immutable_meta_key();
+
+        {
+            let mut uncommitted = self.uncommitted.borrow_mut();
+            if uncommitted.is_empty() {
+                uncommitted.push(HashMap::new());
+            }
+            uncommitted
+                .last_mut()
+                .unwrap()
+                .insert(key.to_vec(), len.to_le_bytes().to_vec());
+        }
+
+        *self.immutable_len.borrow_mut() = Some(len);
+    }
+
+    /// Length of the immutable page region, resolved (and cached) from the side record. Pages whose
+    /// index is below this length may only be written while they hold no committed value (the
+    /// deploy-time write); once committed they can't be overwritten.
+    fn immutable_len(&self) -> u32 {
+        if let Some(len) = *self.immutable_len.borrow() {
+            return len;
+        }
+
+        let key = self.immutable_meta_key();
+        let len = match self.lookup(key.as_ref()) {
+            Some(bytes) if bytes.len() == 4 => {
+                u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            }
+            _ => 0,
+        };
+
+        *self.immutable_len.borrow_mut() = Some(len);
+
+        len
+    }
+
+    /// Page indices that the caller attempted to overwrite after they had already been committed to
+    /// an immutable region. The VM consults this to reject an illegal post-deploy write
+    /// deterministically.
+    #[allow(unused)]
+    pub fn immutable_violations(&self) -> &[PageIndex] {
+        &self.immutable_violations
+    }
+
+    /// Returns `true` when `key` has no committed value yet in the underlying key-value store (i.e a
+    /// `write_page` targeting it is an *initial* write). The determination is memoized so repeated
+    /// queries for the same `key` within one execution don't re-hit the store.
+    fn is_write_initial(&self, key: &[u8]) -> bool {
+        if let Some(&initial) = self.initial_cache.borrow().get(key) {
+            return initial;
+        }
+
+        let initial = self.kv.borrow().get(key).is_none();
+        self.initial_cache.borrow_mut().insert(key.to_vec(), initial);
+
+        initial
+    }
+
+    /// Returns the pending (not-yet-committed) write-set, annotated per page with whether the write
+    /// creates a brand-new `page-key` or overwrites an existing one — see [`PageDiff`]. Intended to
+    /// be consulted right before [`commit`](PagesStorage::commit).
+    #[allow(unused)]
+    pub fn write_set_diff(&self) -> Vec<PageDiff> {
+        self.flatten()
+            .into_iter()
+            .map(|(key, data)| {
+                let is_initial = self.is_write_initial(&key);
+
+                PageDiff {
+                    key,
+                    data,
+                    is_initial,
+                }
+            })
+            .collect()
+    }
+
+    /// Flattens all the pending layers into a single write-set, applying them bottom-up so that the
+    /// top layers override the ones beneath on key conflicts.
+    fn flatten(&self) -> HashMap<Vec<u8>, Vec<u8>> {
+        let mut flat = HashMap::new();
+
+        for layer in self.uncommitted.borrow().iter() {
+            for (key, page) in layer.iter() {
+                flat.insert(key.clone(), page.clone());
+            }
+        }
+
+        flat
+    }
+
     #[cfg(test)]
     pub fn uncommitted_len(&self) -> usize {
-        self.uncommitted.len()
+        self.flatten().len()
     }
 }
 
@@ -70,28 +491,83 @@ where
     KV: KVStore,
 {
     /// We assume that the `page` has no pending changes (see more detailed explanation above).
-    fn read_page(&mut self, page_idx: PageIndex) -> Option<Vec<u8>> {
+    fn read_page(&self, page_idx: PageIndex) -> Option<Vec<u8>> {
         let ph = self.compute_page_hash(page_idx);
 
-        self.kv.borrow().get(&ph)
+        match self.mode {
+            AddressingMode::Direct => self
+                .pending_lookup(ph.as_ref())
+                .or_else(|| self.committed_visible(ph.as_ref())),
+            AddressingMode::ContentAddressed => {
+                let content_hash = self
+                    .pending_lookup(ph.as_ref())
+                    .or_else(|| self.committed_visible(ph.as_ref()))?;
+
+                self.pending_lookup(content_hash.as_ref())
+                    .or_else(|| self.committed_visible(content_hash.as_ref()))
+            }
+        }
     }
 
-    /// Pushes a new pending change (persistence *only* upon `commit`)
+    /// Pushes a new pending change (persistence *only* upon `commit`) onto the top layer.
     fn write_page(&mut self, page_idx: PageIndex, data: &[u8]) {
+        // The reserved index backs the immutable-region side record and is not a writable page.
+        // Rejecting it keeps the documented invariant even though the meta key is also
+        // domain-separated out of the page-key namespace.
+        if page_idx == IMMUTABLE_META_PAGE {
+            return;
+        }
+
         let ph = self.compute_page_hash(page_idx);
 
-        self.uncommitted.insert(ph.to_vec(), data.to_vec());
+        // A page in the immutable region may only be written while it has no committed value (the
+        // single deploy-time write). Any later attempt is recorded and dropped so the VM can reject
+        // it deterministically.
+        if page_idx.0 < self.immutable_len() && !self.is_write_initial(ph.as_ref()) {
+            self.immutable_violations.push(page_idx);
+            return;
+        }
+
+        let mut uncommitted = self.uncommitted.borrow_mut();
+
+        if uncommitted.is_empty() {
+            uncommitted.push(HashMap::new());
+        }
+
+        let top = uncommitted.last_mut().unwrap();
+
+        match self.mode {
+            AddressingMode::Direct => {
+                top.insert(ph.to_vec(), data.to_vec());
+            }
+            AddressingMode::ContentAddressed => {
+                let content_hash = Self::compute_content_hash(data);
+
+                // store the blob once, keyed by its content hash...
+                top.insert(content_hash.to_vec(), data.to_vec());
+                // ...and point the `page-key` at it via a small indirection record.
+                top.insert(ph.to_vec(), content_hash.to_vec());
+            }
+        }
     }
 
     /// Clears the pending channges
     fn clear(&mut self) {
-        self.uncommitted.clear();
+        let mut uncommitted = self.uncommitted.borrow_mut();
+        uncommitted.clear();
+        uncommitted.push(HashMap::new());
+
+        self.read_cache.borrow_mut().clear();
+        self.visible_cache.borrow_mut().clear();
+        self.initial_cache.borrow_mut().clear();
+        self.immutable_violations.clear();
+        *self.immutable_len.borrow_mut() = None;
     }
 
     /// Commits pending changes to the underlying key-value store
     fn commit(&mut self) {
-        let changes: Vec<(&[u8], &[u8])> = self
-            .uncommitted
+        let flat = self.flatten();
+        let changes: Vec<(&[u8], &[u8])> = flat
             .iter()
             .map(|(key, page)| (key.as_ref(), page.as_ref()))
             .collect();
@@ -101,3 +577,283 @@ where
         self.clear();
     }
 }
+
+/// Drives a Percolator-style two-phase commit across a batch of `DefaultPagesStorage` participants
+/// (typically one per address touched by a transaction) so their page changes become visible
+/// atomically. All participants lock against a single designated primary page-key; the commit is
+/// sealed by writing the primary's commit marker once, after every participant has prewritten. A
+/// failure before that marker is written leaves no partially-visible state across contracts.
+///
+/// All participants must share a single underlying key-value store: the commit marker is written
+/// once (through the primary participant) and every participant resolves visibility by reading that
+/// same marker. The coordinator asserts this precondition (`Arc::ptr_eq` on each participant's
+/// store) before prewriting.
+pub struct TwoPhaseCommit {
+    primary_key: Vec<u8>,
+}
+
+impl TwoPhaseCommit {
+    /// Creates a coordinator whose participants will lock against `primary_key`.
+    #[allow(unused)]
+    pub fn new(primary_key: Vec<u8>) -> Self {
+        Self { primary_key }
+    }
+
+    /// Runs both phases over `participants`: first prewrites every participant (phase one), then
+    /// writes the primary's commit marker via the first participant (phase two). The primary
+    /// participant is, by convention, the one at index `0`.
+    #[allow(unused)]
+    pub fn commit<PH, KV>(&self, participants: &mut [DefaultPagesStorage<PH, KV>])
+    where
+        PH: PageIndexHasher,
+        KV: KVStore,
+    {
+        Self::assert_shared_kv(participants);
+
+        for participant in participants.iter_mut() {
+            participant.prewrite(&self.primary_key);
+        }
+
+        if let Some(primary) = participants.first_mut() {
+            primary.commit_primary(&self.primary_key);
+        }
+    }
+
+    /// Aborts the transaction across `participants` by writing the primary's abort marker (through
+    /// the primary participant). Any pages already prewritten are cleaned up on the next read.
+    #[allow(unused)]
+    pub fn rollback<PH, KV>(&self, participants: &mut [DefaultPagesStorage<PH, KV>])
+    where
+        PH: PageIndexHasher,
+        KV: KVStore,
+    {
+        Self::assert_shared_kv(participants);
+
+        if let Some(primary) = participants.first_mut() {
+            primary.rollback_primary(&self.primary_key);
+        }
+    }
+
+    /// Enforces the single-shared-store precondition: every participant must wrap the very same
+    /// `Arc<RefCell<KV>>`, otherwise the commit marker written through the primary would be invisible
+    /// to the others and their prewritten pages would never become visible.
+    fn assert_shared_kv<PH, KV>(participants: &[DefaultPagesStorage<PH, KV>])
+    where
+        PH: PageIndexHasher,
+        KV: KVStore,
+    {
+        if let Some((first, rest)) = participants.split_first() {
+            assert!(
+                rest.iter().all(|p| Arc::ptr_eq(&p.kv, &first.kv)),
+                "TwoPhaseCommit participants must share a single underlying key-value store"
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::page::PageIndex;
+    use crate::traits::{KVStore, PageIndexHasher, PagesStorage};
+
+    use svm_common::Address;
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    /// An in-memory `KVStore` used to exercise the storage layer without a real backend.
+    #[derive(Default)]
+    struct MemKV {
+        map: HashMap<Vec<u8>, Vec<u8>>,
+    }
+
+    impl MemKV {
+        fn len(&self) -> usize {
+            self.map.len()
+        }
+    }
+
+    impl KVStore for MemKV {
+        fn get(&self, key: &[u8]) -> Option<Vec<u8>> {
+            self.map.get(key).cloned()
+        }
+
+        fn store(&mut self, changes: &[(&[u8], &[u8])]) {
+            for (key, value) in changes.iter() {
+                self.map.insert(key.to_vec(), value.to_vec());
+            }
+        }
+
+        fn remove(&mut self, key: &[u8]) {
+            self.map.remove(key);
+        }
+    }
+
+    /// A trivial page-index hasher: the page index alone keys a page. Enough to give each page a
+    /// distinct, stable `page-key` in tests.
+    struct TestHasher;
+
+    impl PageIndexHasher for TestHasher {
+        fn hash(_address: Address, page: PageIndex) -> [u8; 32] {
+            let mut out = [0u8; 32];
+            out[..4].copy_from_slice(&page.0.to_le_bytes());
+            out
+        }
+    }
+
+    fn kv() -> Arc<RefCell<MemKV>> {
+        Arc::new(RefCell::new(MemKV::default()))
+    }
+
+    type Storage = DefaultPagesStorage<TestHasher, MemKV>;
+
+    #[test]
+    fn content_addressed_dedups_identical_pages() {
+        let kv = kv();
+        let mut storage = Storage::new_content_addressed(Address::default(), Arc::clone(&kv));
+
+        let data = vec![7u8; 16];
+        storage.write_page(PageIndex(0), &data);
+        storage.write_page(PageIndex(1), &data);
+        storage.commit();
+
+        // one shared blob + two indirection records — not two copies of the blob.
+        assert_eq!(kv.borrow().len(), 3);
+
+        assert_eq!(storage.read_page(PageIndex(0)), Some(data.clone()));
+        assert_eq!(storage.read_page(PageIndex(1)), Some(data));
+    }
+
+    #[test]
+    fn revert_discards_only_the_inner_layer() {
+        let kv = kv();
+        let mut storage = Storage::new(Address::default(), kv);
+
+        storage.write_page(PageIndex(0), &[1]);
+        storage.checkpoint();
+        storage.write_page(PageIndex(1), &[2]);
+        storage.revert();
+
+        assert_eq!(storage.read_page(PageIndex(0)), Some(vec![1]));
+        assert_eq!(storage.read_page(PageIndex(1)), None);
+    }
+
+    #[test]
+    fn immutable_page_rejects_second_write() {
+        let kv = kv();
+        let mut storage = Storage::new(Address::default(), kv);
+
+        storage.set_immutable_len(1);
+        storage.write_page(PageIndex(0), &[1]);
+        storage.commit();
+
+        // a post-deploy write to the immutable page is dropped and recorded.
+        storage.write_page(PageIndex(0), &[2]);
+
+        assert_eq!(storage.immutable_violations(), &[PageIndex(0)]);
+        assert_eq!(storage.read_page(PageIndex(0)), Some(vec![1]));
+    }
+
+    #[test]
+    fn immutable_metadata_cannot_be_forged_via_reserved_page() {
+        let kv = kv();
+        let mut storage = Storage::new(Address::default(), kv);
+
+        storage.set_immutable_len(1);
+        storage.write_page(PageIndex(0), &[1]);
+        storage.commit();
+
+        // attempt to wipe the immutable-region length through the reserved page index...
+        storage.write_page(PageIndex(u32::MAX), &0u32.to_le_bytes());
+
+        // ...the forging write is dropped and the immutable guard still rejects the second write.
+        storage.write_page(PageIndex(0), &[2]);
+        assert_eq!(storage.immutable_violations(), &[PageIndex(0)]);
+        assert_eq!(storage.read_page(PageIndex(0)), Some(vec![1]));
+    }
+
+    #[test]
+    fn two_phase_page_is_invisible_until_primary_commits() {
+        let kv = kv();
+        let primary = vec![0xAB; 32];
+
+        let mut a = Storage::new(Address::default(), Arc::clone(&kv));
+        let mut b = Storage::new(Address::default(), Arc::clone(&kv));
+        a.write_page(PageIndex(0), &[10]);
+        b.write_page(PageIndex(1), &[20]);
+
+        // prewrite only: both participants have persisted their pages but under an uncommitted lock.
+        let mut participants = [a, b];
+        for p in participants.iter_mut() {
+            p.prewrite(&primary);
+        }
+        assert_eq!(participants[0].read_page(PageIndex(0)), None);
+        assert_eq!(participants[1].read_page(PageIndex(1)), None);
+
+        // sealing the primary flips every prewritten page to visible.
+        participants[0].commit_primary(&primary);
+        assert_eq!(participants[0].read_page(PageIndex(0)), Some(vec![10]));
+        assert_eq!(participants[1].read_page(PageIndex(1)), Some(vec![20]));
+    }
+
+    #[test]
+    fn two_phase_rollback_cleans_up_stale_locks() {
+        let kv = kv();
+        let primary = vec![0xCD; 32];
+
+        let mut a = Storage::new(Address::default(), Arc::clone(&kv));
+        a.write_page(PageIndex(0), &[10]);
+
+        a.prewrite(&primary);
+        assert_eq!(a.read_page(PageIndex(0)), None);
+
+        // aborting the primary keeps the prewritten page invisible for good, and the next read
+        // physically reclaims the orphaned lock and page data rather than merely hiding them.
+        a.rollback_primary(&primary);
+        assert_eq!(a.read_page(PageIndex(0)), None);
+        assert_eq!(kv.borrow().len(), 1); // only the abort marker remains
+    }
+
+    #[test]
+    fn content_addressed_blob_survives_unrelated_aborted_prewrite() {
+        let kv = kv();
+        let mut s = Storage::new_content_addressed(Address::default(), Arc::clone(&kv));
+        let primary1 = vec![0x11; 32];
+        let primary2 = vec![0x22; 32];
+        let data = vec![9u8; 8];
+
+        // tx1: commit page 0 holding `data`, materializing the shared blob.
+        s.write_page(PageIndex(0), &data);
+        s.prewrite(&primary1);
+        s.commit_primary(&primary1);
+        assert_eq!(s.read_page(PageIndex(0)), Some(data.clone()));
+
+        // tx2: prewrite page 1 holding the *same* content (so it reuses the committed blob), then
+        // abort. The blob is already committed, so prewrite must not relock it.
+        s.write_page(PageIndex(1), &data);
+        s.prewrite(&primary2);
+        s.rollback_primary(&primary2);
+
+        // the shared blob — and page 0 that depends on it — survive tx2's abort.
+        assert_eq!(s.read_page(PageIndex(0)), Some(data));
+    }
+
+    #[test]
+    fn coordinator_commits_all_participants_atomically() {
+        let kv = kv();
+
+        let mut a = Storage::new(Address::default(), Arc::clone(&kv));
+        let mut b = Storage::new(Address::default(), Arc::clone(&kv));
+        a.write_page(PageIndex(0), &[10]);
+        b.write_page(PageIndex(1), &[20]);
+
+        let mut participants = [a, b];
+        TwoPhaseCommit::new(vec![0xEE; 32]).commit(&mut participants);
+
+        assert_eq!(participants[0].read_page(PageIndex(0)), Some(vec![10]));
+        assert_eq!(participants[1].read_page(PageIndex(1)), Some(vec![20]));
+    }
+}
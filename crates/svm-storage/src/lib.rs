@@ -0,0 +1,3 @@
+pub mod default;
+pub mod page;
+pub mod traits;
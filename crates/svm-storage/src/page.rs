@@ -0,0 +1,7 @@
+/// A zero-based index identifying a single fixed-size page within a Smart Contract's storage.
+///
+/// Pages are the unit the `PagesStorage` trait reads and writes. A `PageIndex` is turned into a
+/// `page-key` for the underlying key-value store by a `PageIndexHasher` (which also mixes in the
+/// owning contract's `Address`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PageIndex(pub u32);
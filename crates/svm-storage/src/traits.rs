@@ -0,0 +1,49 @@
+use crate::page::PageIndex;
+
+use svm_common::Address;
+
+/// A minimal key-value store abstraction the storage layer persists pages through.
+///
+/// Implementations wrap a concrete backend (an in-memory map for tests, a Trie-backed `leveldb` /
+/// `rocksdb` store in production). Only the operations the storage layer needs are exposed: a
+/// point `get`, a batched `store` (so backends that support atomic batch writes can take
+/// advantage of it) and a point `remove`.
+pub trait KVStore {
+    /// Returns the value previously stored under `key`, or `None` when the key is absent.
+    fn get(&self, key: &[u8]) -> Option<Vec<u8>>;
+
+    /// Persists each `(key, value)` pair. Backends that support it should apply the batch
+    /// atomically.
+    fn store(&mut self, changes: &[(&[u8], &[u8])]);
+
+    /// Deletes `key`, a no-op when it is already absent. Used by the storage layer to reclaim stale
+    /// two-phase-commit lock records and the orphaned pages of an aborted prewrite.
+    fn remove(&mut self, key: &[u8]);
+}
+
+/// Derives the `page-key` a page is stored under from its owning contract `Address` and its
+/// `PageIndex`. Kept a trait so the hashing scheme can be swapped without touching the storage
+/// layer.
+pub trait PageIndexHasher {
+    /// Hashes `(address, page)` into the 32-byte `page-key`.
+    fn hash(address: Address, page: PageIndex) -> [u8; 32];
+}
+
+/// The contract-facing view of page storage.
+///
+/// `read_page` takes `&self`: reads don't require exclusive access, so a read-through cache can
+/// record hits during an otherwise-immutable getter without propagating `&mut` up the execution
+/// stack. The mutating operations (`write_page`, `clear`, `commit`) still take `&mut self`.
+pub trait PagesStorage {
+    /// Reads the page at `page_idx`, returning its bytes or `None` when the page was never written.
+    fn read_page(&self, page_idx: PageIndex) -> Option<Vec<u8>>;
+
+    /// Stages a write for `page_idx`; the change is only persisted on the next `commit`.
+    fn write_page(&mut self, page_idx: PageIndex, data: &[u8]);
+
+    /// Discards all pending (not-yet-committed) changes.
+    fn clear(&mut self);
+
+    /// Flushes all pending changes to the underlying key-value store.
+    fn commit(&mut self);
+}